@@ -0,0 +1,38 @@
+//! Structured (JSON) output, for editors/LSP tooling that want the same
+//! data the text renderer uses without scraping the pretty-printed frame.
+
+use serde::Serialize;
+
+/// Selects what `code_frame` produces: a pretty-printed terminal string
+/// (`Text`, the default) or a [`FrameData`] serialized to JSON (`Json`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// One marker span on a `FrameLine`, in display columns, plus the message
+/// (if any) the text renderer would print alongside its carets.
+#[derive(Debug, Serialize)]
+pub struct FrameMarker {
+    pub start_col: usize,
+    pub len: usize,
+    pub message: Option<String>,
+}
+
+/// One context line plus the marker spans that fall on it.
+#[derive(Debug, Serialize)]
+pub struct FrameLine {
+    pub number: usize,
+    pub text: String,
+    pub markers: Vec<FrameMarker>,
+}
+
+/// The machine-readable equivalent of a rendered frame: the context line
+/// range plus each line's text and marker spans.
+#[derive(Debug, Serialize)]
+pub struct FrameData {
+    pub start: usize,
+    pub end: usize,
+    pub lines: Vec<FrameLine>,
+}
@@ -1,4 +1,15 @@
-use std::{cmp::max, collections::HashMap};
+use std::{cmp::max, collections::HashMap, ops::Range};
+
+mod json;
+mod source_map;
+mod style;
+mod width;
+
+pub use json::{Format, FrameData, FrameLine, FrameMarker};
+pub use source_map::SourceMap;
+pub use style::{AnsiStyle, Style, Theme};
+use style::StyledBuffer;
+use width::{display_column, display_width};
 
 pub struct NodeLocation {
     pub start: Location,
@@ -8,67 +19,172 @@ pub struct NodeLocation {
 #[derive(Debug)]
 pub struct Location {
     pub line: usize,
+    /// A char index into the line, *not* a display column: a tab and a
+    /// double-width CJK character both occupy one index here but a
+    /// different number of terminal cells. `code_frame` converts this to a
+    /// display column itself (see `CodeFrameOptions::tab_width`) before
+    /// drawing carets, so callers should keep passing char indices.
     pub column: usize,
 }
+
+/// A single labeled span to draw in a frame. Several annotations may land on
+/// the same line; `code_frame` merges them into one underline row, drawing
+/// each annotation's carets at its own column and its message (if any) right
+/// after them.
+pub struct Annotation {
+    pub location: NodeLocation,
+    pub message: Option<String>,
+}
 pub struct CodeFrameOptions {
     pub lines_above: usize,
     pub lines_below: usize,
+    /// When set, the frame is rendered with ANSI escape codes driven by this
+    /// theme. When `None` (the default), `code_frame` emits plain text.
+    pub theme: Option<Theme>,
+    /// How many display columns a tab advances to the next stop of. Used to
+    /// convert `Location::column` char indices to display columns.
+    pub tab_width: usize,
+    /// Whether `code_frame` renders a pretty-printed string (the default) or
+    /// a JSON-serialized [`FrameData`].
+    pub format: Format,
+    /// Lines (by the same 0-indexed numbering as `Location::line`) to mark
+    /// with the `>` focus arrow even if they carry no caret underline. Useful
+    /// for showing a block of relevant context, like a whole matched `if`
+    /// body, alongside the precise caret.
+    pub highlight_lines: Vec<usize>,
+}
+
+impl Default for CodeFrameOptions {
+    fn default() -> CodeFrameOptions {
+        CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 0,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        }
+    }
+}
+
+/// One annotation's caret span on a single line, plus its message if this is
+/// the line the message should be printed on (the last line of the span).
+#[derive(Debug)]
+struct MarkerSpan {
+    start_col: usize,
+    len: usize,
+    message: Option<String>,
 }
 
-type LineMarkers =
-    HashMap</* line_number: */ usize, (/* start_col: */ usize, /* len: */ usize)>;
+type LineMarkers = HashMap</* line_number: */ usize, Vec<MarkerSpan>>;
 
 #[derive(Debug)]
 pub struct MarkerLines {
     pub start: usize,
     pub end: usize,
-    pub marker_lines: LineMarkers,
+    marker_lines: LineMarkers,
 }
 
-fn marker_lines(lines: &[&str], loc: NodeLocation, options: CodeFrameOptions) -> MarkerLines {
-    let lines_above = options.lines_above;
-    let lines_below = options.lines_below;
+fn insert_annotation(
+    lines: &[&str],
+    annotation: Annotation,
+    tab_width: usize,
+    marker_lines: &mut LineMarkers,
+) {
+    let loc = annotation.location;
+    let message = annotation.message;
 
-    // Note: These are not 0-indexed
+    // Note: These are 0-indexed. Columns are char indices; we convert
+    // each one to a display column (tabs, wide graphemes, ...) below.
     let start_line = loc.start.line;
     let start_col = loc.start.column;
     let end_line = loc.end.line;
     let end_col = loc.end.column;
 
-    let start = ((start_line as i32) - (lines_above as i32)).max(0) as usize;
-    let end = lines.len().min(end_line + lines_below).max(0);
     let line_diff = end_line - start_line;
 
-    let mut marker_lines: LineMarkers = HashMap::new();
+    let mut push = |line_number: usize, start_col: usize, len: usize, message: Option<String>| {
+        marker_lines
+            .entry(line_number)
+            .or_default()
+            .push(MarkerSpan {
+                start_col,
+                len,
+                message,
+            });
+    };
 
     if line_diff > 0 {
         // The marker spans multiple lines
         for i in 0..=line_diff {
             let line_number = i + start_line;
+            let line = lines[line_number];
             if i == 0 {
                 // The first line
-                marker_lines.insert(
+                let display_start = display_column(line, start_col, tab_width);
+                push(
                     line_number,
-                    (start_col, lines[line_number].len() - start_col),
+                    display_start,
+                    display_width(line, tab_width) - display_start,
+                    None,
                 );
             } else if i == line_diff {
-                // The last line
-                marker_lines.insert(line_number, (0, end_col));
+                // The last line; this is where the message is printed
+                push(
+                    line_number,
+                    0,
+                    display_column(line, end_col, tab_width),
+                    message.clone(),
+                );
             } else {
                 // A line in the middle
-                marker_lines.insert(line_number, (0, lines[line_number].len()));
+                push(line_number, 0, display_width(line, tab_width), None);
             }
         }
     } else {
         // The marker is on a single line
+        let line = lines[start_line];
+        let display_start = display_column(line, start_col, tab_width);
+        let display_end = display_column(line, end_col, tab_width);
         if start_col == end_col {
             // The marker is a single character
-            marker_lines.insert(start_line, (start_col, 0));
+            push(start_line, display_start, 0, message.clone());
         } else {
             // The marker is a range of characters
-            marker_lines.insert(start_line, (start_col, end_col - start_col));
+            push(
+                start_line,
+                display_start,
+                display_end - display_start,
+                message.clone(),
+            );
         }
     }
+}
+
+fn marker_lines(
+    lines: &[&str],
+    annotations: Vec<Annotation>,
+    options: CodeFrameOptions,
+) -> MarkerLines {
+    let lines_above = options.lines_above;
+    let lines_below = options.lines_below;
+    let tab_width = options.tab_width;
+
+    let start = annotations
+        .iter()
+        .map(|a| ((a.location.start.line as i32) - (lines_above as i32)).max(0) as usize)
+        .min()
+        .unwrap_or(0);
+    let end = annotations
+        .iter()
+        .map(|a| lines.len().min(a.location.end.line + lines_below))
+        .max()
+        .unwrap_or(0);
+
+    let mut marker_lines: LineMarkers = HashMap::new();
+    for annotation in annotations {
+        insert_annotation(lines, annotation, tab_width, &mut marker_lines);
+    }
 
     MarkerLines {
         start,
@@ -77,47 +193,171 @@ fn marker_lines(lines: &[&str], loc: NodeLocation, options: CodeFrameOptions) ->
     }
 }
 
-pub fn code_frame(lines: &[&str], loc: NodeLocation, context_window: CodeFrameOptions) -> String {
-    let marker_lines = marker_lines(lines, loc, context_window);
+/// Renders a single underline row covering every span on one line, sorted
+/// left to right. Each annotation's carets are drawn at its own
+/// column, and its message (if any) is printed immediately after them.
+fn render_marker_row(spans: &[MarkerSpan]) -> (String, String) {
+    let mut sorted: Vec<&MarkerSpan> = spans.iter().collect();
+    sorted.sort_by_key(|s| s.start_col);
+
+    let mut row = String::new();
+    let mut cursor = 0;
+    for span in sorted {
+        // If this span's real column was already passed (e.g. the previous
+        // span's message ran past it), clamp it to the cursor but still
+        // separate it with a space so the two groups don't visually merge.
+        let overlapping = span.start_col < cursor;
+        let start_col = span.start_col.max(cursor);
+        let mut padding = start_col - row.chars().count();
+        if overlapping && padding == 0 {
+            padding = 1;
+        }
+        row.push_str(&" ".repeat(padding));
+        row.push_str(&"^".repeat(max(span.len, 1)));
+        if let Some(message) = &span.message {
+            row.push(' ');
+            row.push_str(message);
+        }
+        cursor = row.chars().count();
+    }
+
+    let leading_spaces = row.chars().take_while(|c| *c == ' ').count();
+    (" ".repeat(leading_spaces), row[leading_spaces..].to_string())
+}
+
+pub fn code_frame(
+    lines: &[&str],
+    annotations: Vec<Annotation>,
+    context_window: CodeFrameOptions,
+) -> String {
+    let theme = context_window.theme;
+    let format = context_window.format;
+    let highlight_lines = context_window.highlight_lines.clone();
+    let marker_lines = marker_lines(lines, annotations, context_window);
 
-    let max_line_number_width = marker_lines.end.to_string().len() + 1;
     let context = &lines[marker_lines.start..marker_lines.end];
 
-    context
+    match format {
+        Format::Text => render_text(context, &marker_lines, theme, &highlight_lines),
+        Format::Json => render_json(context, &marker_lines),
+    }
+}
+
+fn render_text(
+    context: &[&str],
+    marker_lines: &MarkerLines,
+    theme: Option<Theme>,
+    highlight_lines: &[usize],
+) -> String {
+    let max_line_number_width = marker_lines.end.to_string().len() + 1;
+
+    let mut buffer = StyledBuffer::new();
+
+    for (i, line) in context.iter().enumerate() {
+        // The line number is 0-indexed, like `Location::line`
+        let line_number = i + marker_lines.start;
+        let line_number_width = line_number.to_string().len();
+        let line_number_padding = " ".repeat(max_line_number_width - line_number_width);
+
+        let marker = marker_lines
+            .marker_lines
+            .get(&line_number)
+            .map(|spans| render_marker_row(spans));
+        let highlighted = highlight_lines.contains(&line_number);
+
+        let row = buffer.new_row();
+        if marker.is_some() || highlighted {
+            buffer.append(row, "> ", Style::FocusArrow);
+        } else {
+            buffer.append(row, "  ", Style::Normal);
+        }
+        buffer.append(row, &line_number.to_string(), Style::LineNumber);
+        buffer.append(row, " | ", Style::Separator);
+        buffer.append(
+            row,
+            line,
+            if highlighted {
+                Style::Highlight
+            } else {
+                Style::Normal
+            },
+        );
+
+        if let Some((marker_padding, marker)) = marker {
+            let marker_row = buffer.new_row();
+            buffer.append(marker_row, "  ", Style::Normal);
+            buffer.append(marker_row, &line_number_padding, Style::LineNumber);
+            buffer.append(marker_row, " | ", Style::Separator);
+            buffer.append(marker_row, &marker_padding, Style::Normal);
+            buffer.append(marker_row, &marker, Style::Underline);
+        }
+    }
+
+    match theme {
+        Some(theme) => buffer.render_ansi(&theme),
+        None => buffer.render_plain(),
+    }
+}
+
+fn render_json(context: &[&str], marker_lines: &MarkerLines) -> String {
+    let lines = context
         .iter()
         .enumerate()
         .map(|(i, line)| {
-            // Adjust the line number to be 1-indexed
             let line_number = i + marker_lines.start;
-            let line_number_width = line_number.to_string().len();
-            let line_number_padding = max_line_number_width - line_number_width;
-            let line_number_padding = " ".repeat(line_number_padding);
-
-            let mut marker_line = None;
-            if let Some((start_col, len)) = marker_lines.marker_lines.get(&line_number) {
-                // We're marking at least 1 character
-                let marker = "^".repeat(max(*len, 1));
-
-                // Pad the marker line with spaces to align with the start column
-                let marker_padding = " ".repeat(*start_col);
-                marker_line = Some(format!(
-                    "{}{}{}",
-                    marker_padding, marker, line_number_padding
-                ));
-            }
+            let markers = marker_lines
+                .marker_lines
+                .get(&line_number)
+                .map(|spans| {
+                    spans
+                        .iter()
+                        .map(|s| FrameMarker {
+                            start_col: s.start_col,
+                            len: s.len,
+                            message: s.message.clone(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
 
-            if let Some(marker_line) = marker_line {
-                // Add a > to the start of the line if it's a marked line
-                return format!(
-                    "> {} | {}\n  {} | {}",
-                    line_number, line, line_number_padding, marker_line
-                );
+            FrameLine {
+                number: line_number,
+                text: line.to_string(),
+                markers,
             }
-            // Otherwise, just print the line number and line
-            format!("  {} | {}", line_number, line)
         })
-        .collect::<Vec<String>>()
-        .join("\n")
+        .collect();
+
+    let data = FrameData {
+        start: marker_lines.start,
+        end: marker_lines.end,
+        lines,
+    };
+
+    serde_json::to_string(&data).expect("FrameData contains only serializable fields")
+}
+
+/// Renders a frame from a raw source string and a byte-offset span, for
+/// callers (lexers/parsers) that only have byte positions on hand. Builds a
+/// [`SourceMap`] to resolve `span` into a `NodeLocation` and delegates to
+/// [`code_frame`].
+pub fn code_frame_bytes(source: &str, span: Range<usize>, options: CodeFrameOptions) -> String {
+    let map = SourceMap::new(source);
+    let lines = map.lines();
+
+    let loc = NodeLocation {
+        start: map.lookup(span.start),
+        end: map.lookup(span.end),
+    };
+
+    code_frame(
+        &lines,
+        vec![Annotation {
+            location: loc,
+            message: None,
+        }],
+        options,
+    )
 }
 
 #[cfg(test)]
@@ -137,9 +377,17 @@ mod tests {
         let context_window = CodeFrameOptions {
             lines_above: 3,
             lines_below: 3,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
         };
 
-        let res = code_frame(&lines, loc, context_window);
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
         insta::assert_yaml_snapshot!(res)
     }
 
@@ -157,9 +405,17 @@ mod tests {
         let context_window = CodeFrameOptions {
             lines_above: 0,
             lines_below: 0,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
         };
 
-        let res = code_frame(&lines, loc, context_window);
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
         insta::assert_yaml_snapshot!(res)
     }
 
@@ -189,9 +445,322 @@ mod tests {
         let context_window = CodeFrameOptions {
             lines_above: 10,
             lines_below: 10,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_with_theme() {
+        let code = r#"println!("Hello, world!")"#;
+        let lines = code.trim().lines().collect::<Vec<_>>();
+
+        let loc = NodeLocation {
+            start: Location { line: 0, column: 0 },
+            end: Location { line: 0, column: 7 },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: Some(Theme::default_theme()),
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_multiple_annotations_same_line() {
+        let code = r#"let x: String = 42;"#;
+        let lines = code.trim().lines().collect::<Vec<_>>();
+
+        let annotations = vec![
+            Annotation {
+                location: NodeLocation {
+                    start: Location { line: 0, column: 7 },
+                    end: Location {
+                        line: 0,
+                        column: 13,
+                    },
+                },
+                message: Some("expected due to this".to_string()),
+            },
+            Annotation {
+                location: NodeLocation {
+                    start: Location {
+                        line: 0,
+                        column: 16,
+                    },
+                    end: Location {
+                        line: 0,
+                        column: 18,
+                    },
+                },
+                message: Some("expected String, found i32".to_string()),
+            },
+        ];
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_overlapping_columns() {
+        // Unlike `test_code_frame_multiple_annotations_same_line` (where the
+        // spans are disjoint and only collide because the first message is
+        // long), these two `NodeLocation`s genuinely overlap in columns
+        // 3..5. `render_marker_row` should still clamp the second span to
+        // start one space after the first ends, rather than drawing
+        // overlapping carets.
+        let code = r#"aaaaaaaaaa"#;
+        let lines = code.trim().lines().collect::<Vec<_>>();
+
+        let annotations = vec![
+            Annotation {
+                location: NodeLocation {
+                    start: Location { line: 0, column: 0 },
+                    end: Location { line: 0, column: 5 },
+                },
+                message: None,
+            },
+            Annotation {
+                location: NodeLocation {
+                    start: Location { line: 0, column: 3 },
+                    end: Location { line: 0, column: 8 },
+                },
+                message: None,
+            },
+        ];
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_cjk_alignment() {
+        let code = "let 名前 = 42;";
+        let lines = code.lines().collect::<Vec<_>>();
+
+        // `名前` is two chars wide in display columns each, so the `42`
+        // after it sits further right in display columns than its char index.
+        let loc = NodeLocation {
+            start: Location {
+                line: 0,
+                column: 9,
+            },
+            end: Location {
+                line: 0,
+                column: 11,
+            },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_combining_marks() {
+        // "é" here is "e" + U+0301 COMBINING ACUTE ACCENT: two chars, one
+        // display column.
+        let code = "caf\u{0065}\u{0301} = 1;";
+        let lines = code.lines().collect::<Vec<_>>();
+
+        let loc = NodeLocation {
+            start: Location {
+                line: 0,
+                column: 8,
+            },
+            end: Location {
+                line: 0,
+                column: 9,
+            },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_leading_tabs() {
+        let code = "\t\tlet x = 1;";
+        let lines = code.lines().collect::<Vec<_>>();
+
+        // Two leading tabs expand to 16 display columns (tab_width 8), so
+        // the caret under `x` should line up at display column 20, not at
+        // its char index 6.
+        let loc = NodeLocation {
+            start: Location {
+                line: 0,
+                column: 6,
+            },
+            end: Location {
+                line: 0,
+                column: 7,
+            },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_source_map_lookup() {
+        let source = "fn main() {\n    println!(\"hi\");\n}";
+        let map = SourceMap::new(source);
+
+        // Byte offset of `println` on the second line.
+        let byte_pos = source.find("println").unwrap();
+        let loc = map.lookup(byte_pos);
+
+        assert_eq!(loc.line, 1);
+        assert_eq!(loc.column, 4);
+    }
+
+    #[test]
+    fn test_code_frame_bytes() {
+        let source = "fn main() {\n    println!(\"hi\");\n}";
+        let start = source.find("println").unwrap();
+        let end = start + "println".len();
+
+        let context_window = CodeFrameOptions {
+            lines_above: 1,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            highlight_lines: Vec::new(),
+        };
+
+        let res = code_frame_bytes(source, start..end, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_json() {
+        let code = r#"println!("Hello, world!")"#;
+        let lines = code.trim().lines().collect::<Vec<_>>();
+
+        let loc = NodeLocation {
+            start: Location { line: 0, column: 0 },
+            end: Location { line: 0, column: 7 },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 0,
+            lines_below: 1,
+            theme: None,
+            tab_width: 8,
+            format: Format::Json,
+            highlight_lines: Vec::new(),
+        };
+
+        let annotations = vec![Annotation {
+            location: loc,
+            message: Some("unexpected macro call".to_string()),
+        }];
+        let res = code_frame(&lines, annotations, context_window);
+        insta::assert_yaml_snapshot!(res)
+    }
+
+    #[test]
+    fn test_code_frame_highlight_lines() {
+        let code = "fn main() {\nif true {\nprintln!(\"hi\");\n}\n}";
+        let lines = code.lines().collect::<Vec<_>>();
+
+        let loc = NodeLocation {
+            start: Location { line: 2, column: 0 },
+            end: Location { line: 2, column: 7 },
+        };
+
+        let context_window = CodeFrameOptions {
+            lines_above: 1,
+            lines_below: 2,
+            theme: None,
+            tab_width: 8,
+            format: Format::Text,
+            // Highlight the whole `if` block, not just the caret's line.
+            highlight_lines: vec![1, 2, 3],
         };
 
-        let res = code_frame(&lines, loc, context_window);
+        let annotations = vec![Annotation {
+            location: loc,
+            message: None,
+        }];
+        let res = code_frame(&lines, annotations, context_window);
         insta::assert_yaml_snapshot!(res)
     }
 }
@@ -0,0 +1,49 @@
+//! Maps byte offsets (e.g. lexer/parser spans) to `Location`s, the way a
+//! compiler's codemap resolves an absolute source position to line/column.
+
+use crate::Location;
+
+/// Precomputes line-start byte offsets for a source string so callers with
+/// only byte spans (not pre-split `&[&str]` + `Location`s) can still build a
+/// frame. Build once per source file with [`SourceMap::new`].
+pub struct SourceMap<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> SourceMap<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            source
+                .bytes()
+                .enumerate()
+                .filter(|(_, b)| *b == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+
+        SourceMap {
+            source,
+            line_starts,
+        }
+    }
+
+    /// Resolves a byte offset into the source to a `Location`, finding the
+    /// line via binary search over `line_starts` and the column by counting
+    /// chars from the start of that line.
+    pub fn lookup(&self, byte_pos: usize) -> Location {
+        let line = match self.line_starts.binary_search(&byte_pos) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let line_start = self.line_starts[line];
+        let column = self.source[line_start..byte_pos].chars().count();
+
+        Location { line, column }
+    }
+
+    /// The source split into lines, in the form `code_frame` expects.
+    pub fn lines(&self) -> Vec<&'a str> {
+        self.source.lines().collect()
+    }
+}
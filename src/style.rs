@@ -0,0 +1,135 @@
+//! Styled rendering support, modeled on rustc's `StyledBuffer`.
+//!
+//! `code_frame` lays out the frame as a grid of `(char, Style)` cells instead
+//! of writing directly to a `String`. This keeps the layout logic (gutter
+//! widths, marker padding, etc.) in one place while letting the two terminal
+//! writers below decide how each cell actually gets printed.
+
+/// The role a single character plays in a rendered frame. Each variant maps
+/// to a distinct color/weight in a [`Theme`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// The gutter line number (and its padding).
+    LineNumber,
+    /// The `|` column separator.
+    Separator,
+    /// Ordinary source text.
+    Normal,
+    /// The `^` marker caret underneath a span.
+    Underline,
+    /// The `>` arrow pointing at a focused line.
+    FocusArrow,
+    /// Source text on a whole-line highlight (see `CodeFrameOptions::highlight_lines`).
+    Highlight,
+}
+
+/// A single ANSI escape sequence to apply for a [`Style`], e.g. `"\x1b[1;34m"`.
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiStyle(pub &'static str);
+
+const RESET: &str = "\x1b[0m";
+
+/// Maps each [`Style`] to the ANSI escape code used to render it.
+///
+/// Construct with [`Theme::default_theme`], or override individual fields
+/// for a custom palette.
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub line_number: AnsiStyle,
+    pub separator: AnsiStyle,
+    pub normal: AnsiStyle,
+    pub underline: AnsiStyle,
+    pub focus_arrow: AnsiStyle,
+    pub highlight: AnsiStyle,
+}
+
+impl Theme {
+    /// A reasonable default palette: dim gutter/separator, bold red carets
+    /// and focus arrow, bold source text on highlighted lines, plain source
+    /// text otherwise.
+    pub fn default_theme() -> Theme {
+        Theme {
+            line_number: AnsiStyle("\x1b[2m"),
+            separator: AnsiStyle("\x1b[2m"),
+            normal: AnsiStyle(""),
+            underline: AnsiStyle("\x1b[1;31m"),
+            focus_arrow: AnsiStyle("\x1b[1;31m"),
+            highlight: AnsiStyle("\x1b[1m"),
+        }
+    }
+
+    fn style_for(&self, style: Style) -> AnsiStyle {
+        match style {
+            Style::LineNumber => self.line_number,
+            Style::Separator => self.separator,
+            Style::Normal => self.normal,
+            Style::Underline => self.underline,
+            Style::FocusArrow => self.focus_arrow,
+            Style::Highlight => self.highlight,
+        }
+    }
+}
+
+/// A grid of styled characters, built up line by line and flattened by one
+/// of the terminal writers below.
+#[derive(Debug, Default)]
+pub struct StyledBuffer {
+    rows: Vec<Vec<(char, Style)>>,
+}
+
+impl StyledBuffer {
+    pub fn new() -> StyledBuffer {
+        StyledBuffer { rows: Vec::new() }
+    }
+
+    /// Starts a new row and returns its index.
+    pub fn new_row(&mut self) -> usize {
+        self.rows.push(Vec::new());
+        self.rows.len() - 1
+    }
+
+    /// Appends `text` to `row`, tagging every character with `style`.
+    pub fn append(&mut self, row: usize, text: &str, style: Style) {
+        if row >= self.rows.len() {
+            self.rows.resize_with(row + 1, Vec::new);
+        }
+        self.rows[row].extend(text.chars().map(|c| (c, style)));
+    }
+
+    /// Flattens the buffer to plain text, discarding style information. This
+    /// is the default rendering used when no [`Theme`] is configured.
+    pub fn render_plain(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| row.iter().map(|(c, _)| *c).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Flattens the buffer to text wrapped in ANSI escape codes, switching
+    /// codes whenever the style changes and resetting at the end of each run.
+    pub fn render_ansi(&self, theme: &Theme) -> String {
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut out = String::new();
+                let mut current: Option<Style> = None;
+                for (c, style) in row {
+                    if current != Some(*style) {
+                        if current.is_some() {
+                            out.push_str(RESET);
+                        }
+                        out.push_str(theme.style_for(*style).0);
+                        current = Some(*style);
+                    }
+                    out.push(*c);
+                }
+                if current.is_some() {
+                    out.push_str(RESET);
+                }
+                out
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
@@ -0,0 +1,43 @@
+//! Display-column arithmetic.
+//!
+//! `Location::column` is a char index into its line, but lining up carets
+//! requires the *display* column instead: a tab advances to the next stop of
+//! `tab_width`, and a multibyte grapheme (CJK, combining marks, ...) can be
+//! narrower or wider than one terminal cell. These helpers walk a line
+//! grapheme-by-grapheme (via `unicode-segmentation`) and sum
+//! `unicode-width` widths to get there.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The display column a char index resolves to on `line`, expanding tabs to
+/// the next multiple of `tab_width`.
+///
+/// `char_index` counts chars (code points), not graphemes, matching
+/// `Location::column`. A grapheme cluster can span several chars (e.g. `e` +
+/// a combining accent), so we walk grapheme clusters for width purposes but
+/// only add a cluster's width once all of its chars fall within
+/// `char_index` — otherwise a combining mark would be double-counted as an
+/// extra column of its own.
+pub(crate) fn display_column(line: &str, char_index: usize, tab_width: usize) -> usize {
+    let mut column = 0;
+    let mut chars_seen = 0;
+    for grapheme in line.graphemes(true) {
+        let grapheme_chars = grapheme.chars().count();
+        if chars_seen + grapheme_chars > char_index {
+            break;
+        }
+        if grapheme == "\t" {
+            column = (column / tab_width + 1) * tab_width;
+        } else {
+            column += grapheme.width();
+        }
+        chars_seen += grapheme_chars;
+    }
+    column
+}
+
+/// The number of display columns a line occupies end to end.
+pub(crate) fn display_width(line: &str, tab_width: usize) -> usize {
+    display_column(line, line.chars().count(), tab_width)
+}